@@ -1,6 +1,8 @@
 use anyhow::{Context, Result, anyhow};
-use clap::Parser;
+use chrono::Utc;
+use clap::{Parser, Subcommand};
 use log::*;
+use serde::Serialize;
 use sha2::{Digest, Sha256};
 use std::collections::HashMap;
 use std::fs::{self, Permissions};
@@ -8,10 +10,25 @@ use std::io::Read;
 use std::os::unix::fs::PermissionsExt;
 use std::path::{Path, PathBuf};
 use std::process::{Command, Output};
+use std::time::SystemTime;
 
 #[derive(Parser, Debug)]
-#[command(about = "A tool to backup LUKS headers.")]
+#[command(about = "A tool to backup and restore LUKS headers.")]
 struct Args {
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Back up the LUKS headers of all detected devices.
+    Backup(BackupArgs),
+    /// Restore a previously saved header backup onto a device.
+    Restore(RestoreArgs),
+}
+
+#[derive(Parser, Debug)]
+struct BackupArgs {
     /// Remote SCP destinations (e.g., root@host:/backup/dir/).
     #[arg(long = "remote-path")]
     remote_paths: Vec<String>,
@@ -19,6 +36,52 @@ struct Args {
     /// Local paths to backup headers to.
     #[arg(long = "backup-path")]
     backup_paths: Vec<PathBuf>,
+
+    /// Keep only the newest N header backups per device UUID on each destination
+    /// (--backup-path or --remote-path), deleting older ones. If unset, no pruning is
+    /// performed.
+    #[arg(long)]
+    keep: Option<usize>,
+
+    /// Encrypt each header artifact to this `age` recipient before it is copied to any
+    /// --backup-path/--remote-path; only the resulting .img.age/.txt.age files are copied.
+    #[arg(long = "encrypt-to")]
+    encrypt_to: Option<String>,
+
+    /// After copying, read each artifact back from the destination and verify its SHA256
+    /// matches the source, failing the run on mismatch.
+    #[arg(long)]
+    verify: bool,
+
+    /// Print a summary of devices found, artifacts written, and copies performed/skipped.
+    #[arg(long)]
+    stats: bool,
+}
+
+#[derive(Parser, Debug)]
+struct RestoreArgs {
+    /// Path to a previously saved header backup image
+    /// (luks_header_backup.<hostname>.<uuid>.<short_hash>.img).
+    #[arg(long = "header-backup-file")]
+    header_backup_file: PathBuf,
+
+    /// Device to restore the header onto (e.g. /dev/sdb1).
+    #[arg(long)]
+    device: PathBuf,
+
+    /// Required acknowledgement: restoring overwrites the live key slots.
+    #[arg(long)]
+    force: bool,
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(data);
+    hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
 }
 
 fn run_command(cmd: &mut Command) -> Result<Output> {
@@ -75,11 +138,115 @@ fn get_luks_device_uuid_map() -> Result<HashMap<String, String>> {
     parse_blkid_output(&output_str)
 }
 
+/// Parses a `luks_header_backup.<hostname>.<uuid>.<short_hash>.img` (or `.txt`) filename,
+/// optionally with a trailing `.age` encryption suffix, into its
+/// `(hostname, uuid, short_hash)` parts.
+fn parse_backup_filename(path: &Path) -> Result<(String, String, String)> {
+    let file_name = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| anyhow!("Invalid backup filename: {path:?}"))?;
+    let file_name = file_name.strip_suffix(".age").unwrap_or(file_name);
+
+    let stem = file_name
+        .strip_prefix("luks_header_backup.")
+        .and_then(|s| s.strip_suffix(".img").or_else(|| s.strip_suffix(".txt")))
+        .ok_or_else(|| {
+            anyhow!(
+                "{file_name} does not match the luks_header_backup.<hostname>.<uuid>.<short_hash> pattern"
+            )
+        })?;
+
+    let mut parts: Vec<&str> = stem.split('.').collect();
+    let short_hash = parts
+        .pop()
+        .ok_or_else(|| anyhow!("Could not parse short hash from filename {file_name}"))?;
+    let uuid = parts
+        .pop()
+        .ok_or_else(|| anyhow!("Could not parse UUID from filename {file_name}"))?;
+    if parts.is_empty() {
+        return Err(anyhow!("Could not parse hostname from filename {file_name}"));
+    }
+    let hostname = parts.join(".");
+
+    Ok((hostname, uuid.to_string(), short_hash.to_string()))
+}
+
+/// Returns `"img"` or `"txt"` for a backup artifact filename (encrypted or not), so that
+/// retention keeps headers and their dumps as separate groups.
+fn backup_artifact_kind(file_name: &str) -> Option<&'static str> {
+    let file_name = file_name.strip_suffix(".age").unwrap_or(file_name);
+    if file_name.ends_with(".img") {
+        Some("img")
+    } else if file_name.ends_with(".txt") {
+        Some("txt")
+    } else {
+        None
+    }
+}
+
+/// Deletes all but the newest `keep` backup files per `<hostname>.<uuid>.<kind>` group
+/// present on `backend`, never deleting anything in `just_written`.
+fn prune_old_backups(backend: &dyn StorageBackend, keep: usize, just_written: &[String]) -> Result<()> {
+    let mut groups: HashMap<String, Vec<(String, u64)>> = HashMap::new();
+
+    for (name, mtime) in backend.list_with_mtime()? {
+        let (hostname, uuid, _short_hash) = match parse_backup_filename(Path::new(&name)) {
+            Ok(parts) => parts,
+            Err(_) => continue, // Doesn't match our naming pattern; leave it alone.
+        };
+        let Some(kind) = backup_artifact_kind(&name) else {
+            continue;
+        };
+
+        groups
+            .entry(format!("{hostname}.{uuid}.{kind}"))
+            .or_default()
+            .push((name, mtime));
+    }
+
+    for (group, mut files) in groups {
+        files.sort_by_key(|(_, mtime)| *mtime);
+        let num_to_delete = files.len().saturating_sub(keep);
+        for (name, _) in files.into_iter().take(num_to_delete) {
+            if just_written.iter().any(|w| w == &name) {
+                continue;
+            }
+            info!("Pruning old backup for {group} ({}): {name}", backend.name());
+            backend.remove_file(&name)?;
+        }
+    }
+
+    Ok(())
+}
+
 struct BackupArtifacts {
     uuid: String,
+    device: String,
     img_path: PathBuf,
     txt_path: PathBuf,
     short_hash: String,
+    full_hash: String,
+}
+
+/// Encrypts `path` to `<path>.age` for `recipient` using `age`, sets `0o600` on the
+/// result, removes the plaintext, and returns the encrypted path.
+fn encrypt_artifact(path: &Path, recipient: &str) -> Result<PathBuf> {
+    let encrypted_path = PathBuf::from(format!("{}.age", path.display()));
+
+    let mut cmd = Command::new("age");
+    cmd.arg("-r");
+    cmd.arg(recipient);
+    cmd.arg("-o");
+    cmd.arg(&encrypted_path);
+    cmd.arg(path);
+    run_command(&mut cmd).context("Encrypt backup artifact")?;
+
+    fs::set_permissions(&encrypted_path, Permissions::from_mode(0o600))
+        .context("Set encrypted artifact permissions")?;
+    fs::remove_file(path).context("Failed to remove plaintext artifact after encryption")?;
+
+    Ok(encrypted_path)
 }
 
 fn create_backup_artifacts(
@@ -87,6 +254,7 @@ fn create_backup_artifacts(
     uuid: &str,
     hostname: &str,
     temp_path: &Path,
+    encrypt_to: Option<&str>,
 ) -> Result<BackupArtifacts> {
     info!("Creating backup artifacts for device {device} with UUID {uuid}");
 
@@ -112,10 +280,7 @@ fn create_backup_artifacts(
     file.read_to_end(&mut header_data)
         .context("Failed to read temp file")?;
 
-    let mut hasher = Sha256::new();
-    hasher.update(&header_data);
-    let hash = hasher.finalize();
-    let hash_hex: String = hash.iter().map(|byte| format!("{byte:02x}")).collect();
+    let hash_hex = sha256_hex(&header_data);
     let short_hash = hash_hex[0..8].to_string();
     debug!("Computed SHA256 hash: {hash_hex}");
 
@@ -134,34 +299,390 @@ fn create_backup_artifacts(
     fs::set_permissions(&final_txt_path, Permissions::from_mode(0o600))
         .context("Set txt permissions")?;
 
-    info!("Saved header to {final_img_path:?}");
-    info!("Saved header dump to {final_txt_path:?}");
+    let (img_path, txt_path) = if let Some(recipient) = encrypt_to {
+        info!("Encrypting artifacts for {recipient}");
+        (
+            encrypt_artifact(&final_img_path, recipient)?,
+            encrypt_artifact(&final_txt_path, recipient)?,
+        )
+    } else {
+        (final_img_path, final_txt_path)
+    };
+
+    info!("Saved header to {img_path:?}");
+    info!("Saved header dump to {txt_path:?}");
 
     Ok(BackupArtifacts {
         uuid: uuid.to_string(),
-        img_path: final_img_path,
-        txt_path: final_txt_path,
+        device: device.to_string(),
+        img_path,
+        txt_path,
         short_hash,
+        full_hash: hash_hex,
     })
 }
 
-fn main() -> Result<()> {
-    if std::env::var_os("RUST_LOG").is_none() {
-        unsafe {
-            std::env::set_var("RUST_LOG", "info");
+#[derive(Serialize)]
+struct ManifestEntry {
+    uuid: String,
+    device: String,
+    sha256: String,
+    img_file: String,
+    txt_file: String,
+    img_size: u64,
+    txt_size: u64,
+    timestamp: String,
+    hostname: String,
+    cryptsetup_version: String,
+}
+
+#[derive(Serialize)]
+struct Manifest {
+    entries: Vec<ManifestEntry>,
+}
+
+fn cryptsetup_version() -> Result<String> {
+    let mut cmd = Command::new("cryptsetup");
+    cmd.arg("--version");
+    let output = run_command(&mut cmd).context("Query cryptsetup version")?;
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Returns the `luks_header_backup.<hostname>.manifest.json` file name used for the single
+/// manifest covering a whole backup run.
+fn manifest_file_name(hostname: &str) -> String {
+    format!("luks_header_backup.{hostname}.manifest.json")
+}
+
+/// Builds the single `Manifest` covering every artifact in `artifacts`, stat'ing each
+/// artifact's files on disk for their sizes.
+fn build_manifest(
+    artifacts: &[BackupArtifacts],
+    hostname: &str,
+    cryptsetup_version: &str,
+    timestamp: &str,
+) -> Result<Manifest> {
+    let mut entries = Vec::new();
+    for artifact in artifacts {
+        let img_size = fs::metadata(&artifact.img_path)
+            .with_context(|| format!("Failed to stat {:?}", artifact.img_path))?
+            .len();
+        let txt_size = fs::metadata(&artifact.txt_path)
+            .with_context(|| format!("Failed to stat {:?}", artifact.txt_path))?
+            .len();
+
+        entries.push(ManifestEntry {
+            uuid: artifact.uuid.clone(),
+            device: artifact.device.clone(),
+            sha256: artifact.full_hash.clone(),
+            img_file: artifact
+                .img_path
+                .file_name()
+                .unwrap()
+                .to_string_lossy()
+                .to_string(),
+            txt_file: artifact
+                .txt_path
+                .file_name()
+                .unwrap()
+                .to_string_lossy()
+                .to_string(),
+            img_size,
+            txt_size,
+            timestamp: timestamp.to_string(),
+            hostname: hostname.to_string(),
+            cryptsetup_version: cryptsetup_version.to_string(),
+        });
+    }
+
+    Ok(Manifest { entries })
+}
+
+/// Writes a `luks_header_backup.<hostname>.manifest.json` describing every artifact in
+/// `artifacts`, and returns its path.
+fn write_manifest(
+    artifacts: &[BackupArtifacts],
+    hostname: &str,
+    temp_path: &Path,
+) -> Result<PathBuf> {
+    let cryptsetup_version = cryptsetup_version()?;
+    let timestamp = Utc::now().to_rfc3339();
+    let manifest = build_manifest(artifacts, hostname, &cryptsetup_version, &timestamp)?;
+
+    let manifest_path = temp_path.join(manifest_file_name(hostname));
+    let json =
+        serde_json::to_string_pretty(&manifest).context("Failed to serialize manifest")?;
+    fs::write(&manifest_path, json).context("Failed to write manifest")?;
+    fs::set_permissions(&manifest_path, Permissions::from_mode(0o600))
+        .context("Set manifest permissions")?;
+
+    info!("Wrote manifest to {manifest_path:?}");
+    Ok(manifest_path)
+}
+
+/// A destination that backup artifacts can be copied to and listed from.
+trait StorageBackend {
+    /// A human-readable description of this destination, used in log messages.
+    fn name(&self) -> String;
+
+    /// Copies the file at `path` to this destination, preserving its file name.
+    fn store_file(&self, path: &Path) -> Result<()>;
+
+    /// Lists the file names currently present at this destination.
+    fn list(&self) -> Result<Vec<String>>;
+
+    /// Reads back the stored copy of `file_name` and returns its SHA256 hex digest.
+    fn read_back_hash(&self, file_name: &str) -> Result<String>;
+
+    /// Lists the file names currently present at this destination along with each one's
+    /// modification time, as seconds since the Unix epoch. Used for `--keep` retention.
+    fn list_with_mtime(&self) -> Result<Vec<(String, u64)>>;
+
+    /// Deletes `file_name` from this destination. Used for `--keep` retention.
+    fn remove_file(&self, file_name: &str) -> Result<()>;
+
+    /// Copies both files of a backup artifact to this destination.
+    fn store(&self, artifact: &BackupArtifacts) -> Result<()> {
+        self.store_file(&artifact.img_path)?;
+        self.store_file(&artifact.txt_path)
+    }
+}
+
+struct LocalBackend {
+    path: PathBuf,
+}
+
+impl StorageBackend for LocalBackend {
+    fn name(&self) -> String {
+        format!("local path {:?}", self.path)
+    }
+
+    fn store_file(&self, path: &Path) -> Result<()> {
+        fs::create_dir_all(&self.path).context("Failed to create backup directory")?;
+
+        let file_name = path
+            .file_name()
+            .ok_or_else(|| anyhow!("{path:?} has no file name"))?;
+        let dest_path = self.path.join(file_name);
+        let tmp_path = self
+            .path
+            .join(format!(".{}.tmp", file_name.to_str().unwrap()));
+
+        fs::copy(path, &tmp_path)
+            .with_context(|| format!("Failed to copy {path:?} to {tmp_path:?}"))?;
+        fs::rename(&tmp_path, &dest_path)
+            .with_context(|| format!("Failed to rename {tmp_path:?} to {dest_path:?}"))?;
+
+        info!("Saved {path:?} to {dest_path:?}");
+        Ok(())
+    }
+
+    fn list(&self) -> Result<Vec<String>> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
         }
+
+        let mut names = Vec::new();
+        for entry in
+            fs::read_dir(&self.path).with_context(|| format!("Failed to read {:?}", self.path))?
+        {
+            if let Some(name) = entry?.file_name().to_str() {
+                names.push(name.to_string());
+            }
+        }
+        Ok(names)
     }
-    env_logger::init();
 
-    let args = Args::parse();
-    debug!("{args:?}");
+    fn read_back_hash(&self, file_name: &str) -> Result<String> {
+        let dest_path = self.path.join(file_name);
+        let data = fs::read(&dest_path)
+            .with_context(|| format!("Failed to read back {dest_path:?}"))?;
+        Ok(sha256_hex(&data))
+    }
 
-    if args.remote_paths.is_empty() && args.backup_paths.is_empty() {
-        anyhow::bail!("At least one of --remote-path or --backup-path must be provided.");
+    fn list_with_mtime(&self) -> Result<Vec<(String, u64)>> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut entries = Vec::new();
+        for entry in
+            fs::read_dir(&self.path).with_context(|| format!("Failed to read {:?}", self.path))?
+        {
+            let entry = entry?;
+            if !entry.path().is_file() {
+                continue;
+            }
+            let Some(name) = entry.file_name().to_str().map(|s| s.to_string()) else {
+                continue;
+            };
+            let mtime = entry
+                .metadata()
+                .and_then(|m| m.modified())
+                .with_context(|| format!("Failed to read mtime of {:?}", entry.path()))?
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .context("File mtime is before the Unix epoch")?
+                .as_secs();
+            entries.push((name, mtime));
+        }
+        Ok(entries)
     }
 
-    if !nix::unistd::getuid().is_root() {
-        anyhow::bail!("This program must be run as root");
+    fn remove_file(&self, file_name: &str) -> Result<()> {
+        let path = self.path.join(file_name);
+        fs::remove_file(&path).with_context(|| format!("Failed to remove {path:?}"))
+    }
+}
+
+/// Single-quotes `s` for safe interpolation into a remote shell command string run via
+/// `ssh host "<cmd>"`, escaping any embedded single quotes.
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+struct ScpBackend {
+    remote: String,
+}
+
+impl ScpBackend {
+    /// Splits the `host:path` remote destination into its two parts.
+    fn host_and_dir(&self) -> Result<(&str, &str)> {
+        self.remote
+            .split_once(':')
+            .ok_or_else(|| anyhow!("Remote path {} is not in host:path form", self.remote))
+    }
+}
+
+impl StorageBackend for ScpBackend {
+    fn name(&self) -> String {
+        format!("remote {}", self.remote)
+    }
+
+    fn store_file(&self, path: &Path) -> Result<()> {
+        let mut cmd = Command::new("scp");
+        cmd.args(["-o", "StrictHostKeyChecking=yes", "-o", "BatchMode=yes"]);
+        cmd.arg(path);
+        cmd.arg(&self.remote);
+        run_command(&mut cmd)
+            .with_context(|| format!("Failed to copy {path:?} to {}", self.remote))?;
+        Ok(())
+    }
+
+    fn list(&self) -> Result<Vec<String>> {
+        let (host, remote_dir) = self.host_and_dir()?;
+
+        let mut cmd = Command::new("ssh");
+        cmd.args(["-o", "StrictHostKeyChecking=yes", "-o", "BatchMode=yes"]);
+        cmd.arg(host);
+        cmd.arg(format!("ls -1 {}", shell_quote(remote_dir)));
+        let output = run_command(&mut cmd)
+            .with_context(|| format!("Failed to list {}", self.remote))?;
+
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(|line| line.to_string())
+            .collect())
+    }
+
+    fn read_back_hash(&self, file_name: &str) -> Result<String> {
+        let (host, remote_dir) = self.host_and_dir()?;
+        let remote_path = format!("{}/{file_name}", remote_dir.trim_end_matches('/'));
+
+        let mut cmd = Command::new("ssh");
+        cmd.args(["-o", "StrictHostKeyChecking=yes", "-o", "BatchMode=yes"]);
+        cmd.arg(host);
+        cmd.arg(format!("sha256sum {}", shell_quote(&remote_path)));
+        let output = run_command(&mut cmd)
+            .with_context(|| format!("Failed to sha256sum {remote_path} on {host}"))?;
+
+        let output_str = String::from_utf8_lossy(&output.stdout);
+        let hash = output_str
+            .split_whitespace()
+            .next()
+            .ok_or_else(|| anyhow!("Empty sha256sum output for {remote_path}"))?;
+        Ok(hash.to_string())
+    }
+
+    fn list_with_mtime(&self) -> Result<Vec<(String, u64)>> {
+        let (host, remote_dir) = self.host_and_dir()?;
+
+        let mut cmd = Command::new("ssh");
+        cmd.args(["-o", "StrictHostKeyChecking=yes", "-o", "BatchMode=yes"]);
+        cmd.arg(host);
+        cmd.arg(format!(
+            "find {} -maxdepth 1 -type f -printf '%T@ %f\\n'",
+            shell_quote(remote_dir)
+        ));
+        let output = run_command(&mut cmd)
+            .with_context(|| format!("Failed to list {} with mtimes", self.remote))?;
+
+        let mut entries = Vec::new();
+        for line in String::from_utf8_lossy(&output.stdout).lines() {
+            let (epoch, name) = line
+                .split_once(' ')
+                .ok_or_else(|| anyhow!("Unexpected find output line: {line}"))?;
+            let epoch_secs: u64 = epoch
+                .split('.')
+                .next()
+                .unwrap_or("0")
+                .parse()
+                .with_context(|| format!("Invalid mtime in find output: {line}"))?;
+            entries.push((name.to_string(), epoch_secs));
+        }
+        Ok(entries)
+    }
+
+    fn remove_file(&self, file_name: &str) -> Result<()> {
+        let (host, remote_dir) = self.host_and_dir()?;
+        let remote_path = format!("{}/{file_name}", remote_dir.trim_end_matches('/'));
+
+        let mut cmd = Command::new("ssh");
+        cmd.args(["-o", "StrictHostKeyChecking=yes", "-o", "BatchMode=yes"]);
+        cmd.arg(host);
+        cmd.arg(format!("rm -f {}", shell_quote(&remote_path)));
+        run_command(&mut cmd)
+            .with_context(|| format!("Failed to remove {remote_path} on {host}"))?;
+        Ok(())
+    }
+}
+
+/// Reports whether `artifact` (identified by its UUID and content hash) is already present
+/// among `existing` file names, by parsing each one rather than doing a raw substring match
+/// so an unrelated file that merely contains the same digits can't produce a false positive.
+fn artifact_already_stored(existing: &[String], artifact: &BackupArtifacts) -> bool {
+    existing.iter().any(|name| {
+        matches!(
+            parse_backup_filename(Path::new(name)),
+            Ok((_, uuid, short_hash)) if uuid == artifact.uuid && short_hash == artifact.short_hash
+        )
+    })
+}
+
+/// Reads `source_path` back from `backend` and fails loudly if its hash doesn't match.
+fn verify_stored_file(backend: &dyn StorageBackend, source_path: &Path) -> Result<()> {
+    let file_name = source_path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| anyhow!("{source_path:?} has no file name"))?;
+    let expected_hash = sha256_hex(
+        &fs::read(source_path).with_context(|| format!("Failed to read {source_path:?}"))?,
+    );
+
+    let actual_hash = backend.read_back_hash(file_name)?;
+    if actual_hash != expected_hash {
+        anyhow::bail!(
+            "Verification failed: {file_name} at {} hashes to {actual_hash}, expected {expected_hash}",
+            backend.name()
+        );
+    }
+
+    debug!("Verified {file_name} at {}", backend.name());
+    Ok(())
+}
+
+fn run_backup(args: BackupArgs) -> Result<()> {
+    if args.remote_paths.is_empty() && args.backup_paths.is_empty() {
+        anyhow::bail!("At least one of --remote-path or --backup-path must be provided.");
     }
 
     let hostname = nix::unistd::gethostname()
@@ -182,62 +703,112 @@ fn main() -> Result<()> {
     } else {
         info!("Found {} LUKS devices", device_uuid_map.len());
     }
+    let devices_found = device_uuid_map.len();
 
     let mut artifacts: Vec<BackupArtifacts> = Vec::new();
     for (device, uuid) in device_uuid_map {
-        artifacts.push(create_backup_artifacts(&device, &uuid, &hostname, temp_dir.path())?);
+        artifacts.push(create_backup_artifacts(
+            &device,
+            &uuid,
+            &hostname,
+            temp_dir.path(),
+            args.encrypt_to.as_deref(),
+        )?);
     }
 
-    if !args.backup_paths.is_empty() {
-        for backup_path in &args.backup_paths {
-            info!("Backing up to local path: {backup_path:?}");
-            fs::create_dir_all(backup_path).context("Failed to create backup directory")?;
+    let manifest_path = write_manifest(&artifacts, &hostname, temp_dir.path())?;
+
+    let mut backends: Vec<Box<dyn StorageBackend>> = Vec::new();
+    for backup_path in &args.backup_paths {
+        backends.push(Box::new(LocalBackend {
+            path: backup_path.clone(),
+        }));
+    }
+    for remote in &args.remote_paths {
+        backends.push(Box::new(ScpBackend {
+            remote: remote.clone(),
+        }));
+    }
 
+    let mut all_success = true;
+    let mut copies_performed: usize = 0;
+    let mut copies_skipped: usize = 0;
+
+    for backend in &backends {
+        info!("Backing up to {}", backend.name());
+
+        let existing = backend.list().unwrap_or_else(|e| {
+            debug!("Could not list {}: {e}", backend.name());
+            Vec::new()
+        });
+
+        let result: Result<()> = (|| {
             for artifact in &artifacts {
-                let dest_img_path = backup_path.join(artifact.img_path.file_name().unwrap());
-                let dest_txt_path = backup_path.join(artifact.txt_path.file_name().unwrap());
+                if artifact_already_stored(&existing, artifact) {
+                    info!(
+                        "{}.{} unchanged on {}; skipping copy",
+                        artifact.uuid,
+                        artifact.short_hash,
+                        backend.name()
+                    );
+                    if args.verify {
+                        verify_stored_file(backend.as_ref(), &artifact.img_path)?;
+                        verify_stored_file(backend.as_ref(), &artifact.txt_path)?;
+                    }
+                    copies_skipped += 1;
+                    continue;
+                }
 
-                let tmp_img_path = backup_path.join(format!(".{}.tmp", artifact.img_path.file_name().unwrap().to_str().unwrap()));
-                fs::copy(&artifact.img_path, &tmp_img_path)?;
-                fs::rename(&tmp_img_path, &dest_img_path)?;
+                backend.store(artifact)?;
+                if args.verify {
+                    verify_stored_file(backend.as_ref(), &artifact.img_path)?;
+                    verify_stored_file(backend.as_ref(), &artifact.txt_path)?;
+                }
+                copies_performed += 1;
+            }
 
-                let tmp_txt_path = backup_path.join(format!(".{}.tmp", artifact.txt_path.file_name().unwrap().to_str().unwrap()));
-                fs::copy(&artifact.txt_path, &tmp_txt_path)?;
-                fs::rename(&tmp_txt_path, &dest_txt_path)?;
+            backend.store_file(&manifest_path)?;
+            if args.verify {
+                verify_stored_file(backend.as_ref(), &manifest_path)?;
+            }
+            Ok(())
+        })();
 
-                info!("Saved backup for {} to {dest_img_path:?}", artifact.uuid);
+        match result {
+            Ok(()) => info!("Backup successful to {}", backend.name()),
+            Err(e) => {
+                error!("{e}");
+                all_success = false;
             }
         }
     }
 
-    if !args.remote_paths.is_empty() {
-        let files_to_copy: Vec<PathBuf> = artifacts.iter().flat_map(|a| vec![a.img_path.clone(), a.txt_path.clone()]).collect();
-
-        if files_to_copy.is_empty() {
-            info!("No backups to create for remote locations.");
-        } else {
-            let mut all_success = true;
-            for remote in &args.remote_paths {
-                info!("Pushing to remote: {remote}");
+    if !all_success {
+        anyhow::bail!("Some destinations failed");
+    }
 
-                let mut cmd = Command::new("scp");
-                cmd.args(["-o", "StrictHostKeyChecking=yes", "-o", "BatchMode=yes"]);
-                for path in &files_to_copy {
-                    cmd.arg(path);
-                }
-                cmd.arg(remote);
+    if args.stats {
+        info!(
+            "Stats: {devices_found} devices found, {} artifacts written, {copies_performed} copies performed, {copies_skipped} copies skipped as duplicates",
+            artifacts.len()
+        );
+    }
 
-                if let Err(e) = run_command(&mut cmd) {
-                    error!("{e}");
-                    all_success = false;
-                } else {
-                    info!("Copy successful to {remote}");
-                }
-            }
+    if let Some(keep) = args.keep {
+        let mut written_names: Vec<String> = artifacts
+            .iter()
+            .flat_map(|a| {
+                vec![
+                    a.img_path.file_name().unwrap().to_string_lossy().to_string(),
+                    a.txt_path.file_name().unwrap().to_string_lossy().to_string(),
+                ]
+            })
+            .collect();
+        written_names.push(manifest_path.file_name().unwrap().to_string_lossy().to_string());
 
-            if !all_success {
-                anyhow::bail!("Some remote copies failed");
-            }
+        for backend in &backends {
+            prune_old_backups(backend.as_ref(), keep, &written_names)
+                .with_context(|| format!("Failed to prune old backups on {}", backend.name()))?;
         }
     }
 
@@ -245,10 +816,119 @@ fn main() -> Result<()> {
     Ok(())
 }
 
+fn run_restore(args: RestoreArgs) -> Result<()> {
+    if !args.force {
+        anyhow::bail!(
+            "Restoring a header overwrites the live key slots on {:?}; re-run with --force to confirm",
+            args.device
+        );
+    }
+
+    if args.header_backup_file.extension().and_then(|e| e.to_str()) == Some("age") {
+        anyhow::bail!(
+            "{:?} is encrypted; decrypt it first with `age -d -o <plaintext-path> {:?}` and restore from the plaintext path",
+            args.header_backup_file,
+            args.header_backup_file
+        );
+    }
+
+    let (_hostname, expected_uuid, expected_short_hash) =
+        parse_backup_filename(&args.header_backup_file)?;
+
+    let device_str = args
+        .device
+        .to_str()
+        .ok_or_else(|| anyhow!("Invalid device path: {:?}", args.device))?;
+
+    let device_uuid_map = get_luks_device_uuid_map()?;
+    match device_uuid_map.get(device_str) {
+        Some(current_uuid) if current_uuid != &expected_uuid => {
+            anyhow::bail!(
+                "Refusing to restore: {:?} is for UUID {expected_uuid} but {device_str} currently has UUID {current_uuid}",
+                args.header_backup_file
+            );
+        }
+        Some(_) => {}
+        None => {
+            // A damaged or already-wiped LUKS header is exactly the scenario this feature
+            // exists to recover from, and blkid can no longer see crypto_LUKS/a UUID on such
+            // a device. Don't let that block the recovery; --force and the hash check below
+            // are still required before anything is written.
+            warn!(
+                "{device_str} is not recognized as a LUKS device by blkid, possibly due to a \
+                 damaged header; proceeding without a UUID cross-check because --force was given"
+            );
+        }
+    }
+
+    let mut header_data = Vec::new();
+    let mut file = fs::File::open(&args.header_backup_file)
+        .context("Failed to open header backup file")?;
+    file.read_to_end(&mut header_data)
+        .context("Failed to read header backup file")?;
+
+    let hash_hex = sha256_hex(&header_data);
+    let short_hash = &hash_hex[0..8];
+    if short_hash != expected_short_hash {
+        anyhow::bail!(
+            "Refusing to restore: {:?} hashes to {short_hash} but its filename claims {expected_short_hash}",
+            args.header_backup_file
+        );
+    }
+
+    warn!(
+        "Restoring LUKS header to {device_str} from {:?}",
+        args.header_backup_file
+    );
+
+    let mut cmd = Command::new("cryptsetup");
+    cmd.arg("luksHeaderRestore");
+    cmd.arg(device_str);
+    cmd.arg("--header-backup-file");
+    cmd.arg(&args.header_backup_file);
+    run_command(&mut cmd).context("Restore LUKS header")?;
+
+    info!("Restored LUKS header to {device_str}");
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    if std::env::var_os("RUST_LOG").is_none() {
+        unsafe {
+            std::env::set_var("RUST_LOG", "info");
+        }
+    }
+    env_logger::init();
+
+    let args = Args::parse();
+    debug!("{args:?}");
+
+    if !nix::unistd::getuid().is_root() {
+        anyhow::bail!("This program must be run as root");
+    }
+
+    match args.command {
+        Commands::Backup(backup_args) => run_backup(backup_args),
+        Commands::Restore(restore_args) => run_restore(restore_args),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_sha256_hex() {
+        assert_eq!(
+            sha256_hex(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            sha256_hex(b"hello"),
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+        );
+    }
+
     #[test]
     fn test_parse_blkid_output() -> Result<()> {
         let sample_output = r#"
@@ -289,4 +969,196 @@ TYPE=crypto_LUKS
 
         Ok(())
     }
+
+    #[test]
+    fn test_parse_backup_filename() -> Result<()> {
+        let (hostname, uuid, short_hash) = parse_backup_filename(Path::new(
+            "luks_header_backup.myhost.12345678-1234-1234-1234-123456789abc.deadbeef.img",
+        ))?;
+        assert_eq!(hostname, "myhost");
+        assert_eq!(uuid, "12345678-1234-1234-1234-123456789abc");
+        assert_eq!(short_hash, "deadbeef");
+
+        // Hostnames may themselves contain dots (FQDNs).
+        let (hostname, _, _) = parse_backup_filename(Path::new(
+            "luks_header_backup.host.example.com.12345678-1234-1234-1234-123456789abc.deadbeef.txt",
+        ))?;
+        assert_eq!(hostname, "host.example.com");
+
+        // Encrypted artifacts carry a trailing .age suffix.
+        let (_, uuid, short_hash) = parse_backup_filename(Path::new(
+            "luks_header_backup.myhost.12345678-1234-1234-1234-123456789abc.deadbeef.img.age",
+        ))?;
+        assert_eq!(uuid, "12345678-1234-1234-1234-123456789abc");
+        assert_eq!(short_hash, "deadbeef");
+
+        assert!(parse_backup_filename(Path::new("not_a_backup_file.img")).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_backup_artifact_kind() {
+        assert_eq!(backup_artifact_kind("foo.img"), Some("img"));
+        assert_eq!(backup_artifact_kind("foo.txt"), Some("txt"));
+        assert_eq!(backup_artifact_kind("foo.img.age"), Some("img"));
+        assert_eq!(backup_artifact_kind("foo.txt.age"), Some("txt"));
+        assert_eq!(backup_artifact_kind("foo.json"), None);
+    }
+
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!(shell_quote("/backup/dir"), "'/backup/dir'");
+        assert_eq!(shell_quote("/backup dir/with space"), "'/backup dir/with space'");
+        assert_eq!(
+            shell_quote("/backup/dir; rm -rf /"),
+            "'/backup/dir; rm -rf /'"
+        );
+        assert_eq!(shell_quote("it's/a/dir"), "'it'\\''s/a/dir'");
+    }
+
+    #[test]
+    fn test_prune_old_backups() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let backend = LocalBackend {
+            path: dir.path().to_path_buf(),
+        };
+        let uuid = "12345678-1234-1234-1234-123456789abc";
+
+        let write_generation = |hash: &str, age_secs: u64| -> Result<(String, String)> {
+            let img_name = format!("luks_header_backup.myhost.{uuid}.{hash}.img");
+            let txt_name = format!("luks_header_backup.myhost.{uuid}.{hash}.txt");
+            for name in [&img_name, &txt_name] {
+                let path = dir.path().join(name);
+                fs::write(&path, b"x")?;
+                fs::File::open(&path)?
+                    .set_modified(SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(age_secs))?;
+            }
+            Ok((img_name, txt_name))
+        };
+
+        // Oldest generation: pruneable, but explicitly marked just_written so it must survive.
+        let (oldest_img, oldest_txt) = write_generation("11111111", 100)?;
+        // Middle generation: pruneable and not just_written, so it must be deleted.
+        write_generation("22222222", 200)?;
+        // Newest generation: within `keep`, so it must survive regardless.
+        write_generation("33333333", 300)?;
+
+        prune_old_backups(&backend, 1, &[oldest_img.clone(), oldest_txt.clone()])?;
+
+        let remaining: Vec<String> = backend.list()?;
+        assert_eq!(remaining.len(), 4, "expected only the middle generation to be pruned");
+        assert!(remaining.contains(&oldest_img));
+        assert!(remaining.contains(&oldest_txt));
+        assert!(remaining.contains(&format!("luks_header_backup.myhost.{uuid}.33333333.img")));
+        assert!(remaining.contains(&format!("luks_header_backup.myhost.{uuid}.33333333.txt")));
+        assert!(!remaining.contains(&format!("luks_header_backup.myhost.{uuid}.22222222.img")));
+        assert!(!remaining.contains(&format!("luks_header_backup.myhost.{uuid}.22222222.txt")));
+
+        Ok(())
+    }
+
+    fn fake_artifact(uuid: &str, short_hash: &str) -> BackupArtifacts {
+        BackupArtifacts {
+            uuid: uuid.to_string(),
+            device: "/dev/sda1".to_string(),
+            img_path: PathBuf::from(format!("{uuid}.img")),
+            txt_path: PathBuf::from(format!("{uuid}.txt")),
+            short_hash: short_hash.to_string(),
+            full_hash: format!("{short_hash}full"),
+        }
+    }
+
+    #[test]
+    fn test_artifact_already_stored() {
+        let artifact = fake_artifact("12345678-1234-1234-1234-123456789abc", "deadbeef");
+
+        let existing = vec![
+            "luks_header_backup.myhost.12345678-1234-1234-1234-123456789abc.deadbeef.img"
+                .to_string(),
+        ];
+        assert!(artifact_already_stored(&existing, &artifact));
+
+        // An .age-encrypted copy of the same artifact still counts as stored.
+        let existing_encrypted = vec![
+            "luks_header_backup.myhost.12345678-1234-1234-1234-123456789abc.deadbeef.img.age"
+                .to_string(),
+        ];
+        assert!(artifact_already_stored(&existing_encrypted, &artifact));
+
+        // A file that merely contains the uuid/hash digits as a substring, but doesn't
+        // actually parse as this artifact, must not produce a false positive.
+        let unrelated = vec![format!(
+            "unrelated-file-{}-{}-notes.txt",
+            artifact.uuid, artifact.short_hash
+        )];
+        assert!(!artifact_already_stored(&unrelated, &artifact));
+
+        // A different artifact's files must not match.
+        let other = vec![
+            "luks_header_backup.myhost.87654321-4321-4321-4321-876543210fed.cafebabe.img"
+                .to_string(),
+        ];
+        assert!(!artifact_already_stored(&other, &artifact));
+
+        assert!(!artifact_already_stored(&[], &artifact));
+    }
+
+    #[test]
+    fn test_manifest_file_name() {
+        assert_eq!(
+            manifest_file_name("myhost"),
+            "luks_header_backup.myhost.manifest.json"
+        );
+    }
+
+    #[test]
+    fn test_build_manifest() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let make_artifact = |uuid: &str, short_hash: &str, img: &[u8], txt: &[u8]| -> Result<BackupArtifacts> {
+            let img_path = dir.path().join(format!("luks_header_backup.myhost.{uuid}.{short_hash}.img"));
+            let txt_path = dir.path().join(format!("luks_header_backup.myhost.{uuid}.{short_hash}.txt"));
+            fs::write(&img_path, img)?;
+            fs::write(&txt_path, txt)?;
+            Ok(BackupArtifacts {
+                uuid: uuid.to_string(),
+                device: format!("/dev/{uuid}"),
+                img_path,
+                txt_path,
+                short_hash: short_hash.to_string(),
+                full_hash: format!("{short_hash}full"),
+            })
+        };
+
+        let artifacts = vec![
+            make_artifact("uuid1", "hash1", b"imgdata", b"txtdata12")?,
+            make_artifact("uuid2", "hash2", b"ab", b"cde")?,
+        ];
+
+        let manifest = build_manifest(
+            &artifacts,
+            "myhost",
+            "cryptsetup 2.7.0",
+            "2024-01-02T03:04:05+00:00",
+        )?;
+
+        // One manifest covering every artifact from the run, not one per device.
+        assert_eq!(manifest.entries.len(), 2);
+
+        let entry = &manifest.entries[0];
+        assert_eq!(entry.uuid, "uuid1");
+        assert_eq!(entry.device, "/dev/uuid1");
+        assert_eq!(entry.sha256, "hash1full");
+        assert_eq!(entry.img_file, "luks_header_backup.myhost.uuid1.hash1.img");
+        assert_eq!(entry.txt_file, "luks_header_backup.myhost.uuid1.hash1.txt");
+        assert_eq!(entry.img_size, 7);
+        assert_eq!(entry.txt_size, 9);
+        assert_eq!(entry.hostname, "myhost");
+        assert_eq!(entry.cryptsetup_version, "cryptsetup 2.7.0");
+        assert_eq!(entry.timestamp, "2024-01-02T03:04:05+00:00");
+
+        assert_eq!(manifest.entries[1].uuid, "uuid2");
+
+        Ok(())
+    }
 }